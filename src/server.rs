@@ -1,23 +1,53 @@
 use std::path::Path;
-use std::sync::Arc;
-use std::collections::BinaryHeap;
+use std::sync::{Arc, Mutex};
+use std::collections::{BinaryHeap, HashMap};
+use std::collections::hash_map::DefaultHasher;
 use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::io::Write;
+use std::fs;
 use std::f64;
 use iron::prelude::*;
 use iron::status;
+use iron::headers::{ContentType};
+use iron::response::WriteBody;
+use iron::mime::{Mime, TopLevel, SubLevel};
 use staticfile::Static;
 use mount::Mount;
 use ordered_float::OrderedFloat;
 use urlencoded::UrlEncodedQuery;
 use rustc_serialize::json;
 use time::PreciseTime;
+use rstar::{RTree, RTreeObject, PointDistance, AABB};
 
 #[derive(Debug, Clone)]
 struct HeapEntry {
 	node: usize,
 	cost: f64,
+	priority: f64,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Metric {
+	Distance,
+	Time
+}
+
+// Routing knobs `run_dijkstra` needs beyond the edge-cost function itself,
+// grouped into one argument so the function doesn't grow past clippy's
+// too-many-arguments threshold every time a new one is added.
+#[derive(Debug, Clone, Copy)]
+struct SearchOptions {
+	metric_kind: Metric,
+	use_astar: bool
+}
+
+// rustc_serialize's JSON encoder turns f64::INFINITY into `null`, which fails
+// to decode back into a f64 field, so unreachable nodes in a PrecomputedTable
+// are marked with this large finite sentinel instead.
+const UNREACHABLE_SENTINEL: f64 = 1.0e18;
+
 #[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
 struct RoutingResult {
 	duration: i64,
@@ -31,15 +61,89 @@ struct Route {
 	path: Vec<[f64; 2]>
 }
 
+// Live progress snapshot emitted periodically by `run_dijkstra`/
+// `run_bidirectional_dijkstra`'s optional progress callback while a search is
+// still running.
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+struct SearchState {
+	settled: usize,
+	frontier: usize,
+	best_known: f64,
+	elapsed_ms: i64
+}
+
+// A full single-source Dijkstra run serialized to disk, keyed by
+// (source, vehicle, metric) and guarded by a fingerprint of the graph it was
+// computed against so a stale cache is rejected instead of misrouting.
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+struct PrecomputedTable {
+	source_internal: usize,
+	vehicle: u8,
+	metric: String,
+	graph_fingerprint: u64,
+	distance: Vec<f64>,
+	predecessor: Vec<usize>,
+	predecessor_edge: Vec<usize>
+}
+
 #[derive(Debug, Clone)]
 struct PredecessorInfo {
 	node: usize,
 	edge: usize
 }
 
+// Nearest-node lookup so callers can route from an arbitrary lat/lon instead
+// of an exact OSM node id, plus a transposed copy of the graph so a backward
+// search can run over incoming edges.
+struct RoutingState {
+	data: ::data::RoutingData,
+	node_index: RTree<NodeLocation>,
+	reverse_offset: Vec<usize>,
+	reverse_edges: Vec<ReverseEdge>,
+	precomputed_cache: Mutex<HashMap<(usize, u8, String), Arc<PrecomputedTable>>>,
+	graph_fingerprint: u64
+}
+
+// One incoming edge in the transposed graph: `node` is the original edge's
+// source, `edge` indexes the edge itself in `data.internal_edges` so its
+// length/speed/constraints can be reused unchanged.
+#[derive(Debug, Clone, Copy)]
+struct ReverseEdge {
+	node: usize,
+	edge: usize
+}
+
+#[derive(Debug, Clone, Copy)]
+struct NodeLocation {
+	lat: f64,
+	lon: f64,
+	internal_id: usize
+}
+
+impl RTreeObject for NodeLocation {
+	type Envelope = AABB<[f64; 2]>;
+
+	fn envelope(&self) -> Self::Envelope {
+		AABB::from_point([self.lat, self.lon])
+	}
+}
+
+impl PointDistance for NodeLocation {
+	fn distance_2(&self, point: &[f64; 2]) -> f64 {
+		// Squared raw lat/lon degree deltas don't rank candidates by real-world
+		// distance: longitude degrees shrink by cos(lat) relative to latitude
+		// degrees, so this has to go through `haversine_distance` (meters)
+		// instead, or nearest_neighbor picks the wrong node away from the
+		// equator.
+		let meters = haversine_distance(self.lat, self.lon, point[0], point[1]);
+
+		return meters * meters;
+	}
+}
+
 impl Ord for HeapEntry {
 	fn cmp(&self, other: &HeapEntry) -> Ordering {
-		OrderedFloat(other.cost).cmp(&OrderedFloat(self.cost))
+		OrderedFloat(other.priority).cmp(&OrderedFloat(self.priority))
 	}
 }
 
@@ -54,64 +158,168 @@ impl Eq for HeapEntry {
 
 impl PartialEq for HeapEntry {
 	fn eq(&self, other: &HeapEntry) -> bool {
-		return (self.node == other.node) & &(OrderedFloat(other.cost).eq(&OrderedFloat(self.cost)))
+		return (self.node == other.node) & &(OrderedFloat(other.priority).eq(&OrderedFloat(self.priority)))
 	}
 }
 
+// Great-circle distance between two lat/lon points in meters. Used as the
+// A* heuristic lower bound since it never overestimates the true road distance.
+fn haversine_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+	let earth_radius = 6371000.0;
+
+	let phi1 = lat1.to_radians();
+	let phi2 = lat2.to_radians();
+	let dphi = (lat2 - lat1).to_radians();
+	let dlambda = (lon2 - lon1).to_radians();
+
+	let a = (dphi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (dlambda / 2.0).sin().powi(2);
+	let c = 2.0 * a.sqrt().atan2((1.0 - a).sqrt());
+
+	return earth_radius * c;
+}
+
 
 pub fn start(data: ::data::RoutingData) {
-	let data_wrapped = Arc::new(data);
-	let data_wrapped_2 = data_wrapped.clone();
-	let data_wrapped_3 = data_wrapped.clone();
+	let node_index = RTree::bulk_load(data.osm_nodes.values().map(|node| NodeLocation {
+		lat: node.position.lat,
+		lon: node.position.lon,
+		internal_id: node.internal_id
+	}).collect());
+
+	let (reverse_offset, reverse_edges) = build_reverse_graph(&data);
+
+	// Computed once here rather than per-request: scanning every edge on each
+	// precomputed-table cache miss would make a popular source pay that cost
+	// on every request instead of once at startup.
+	let graph_fingerprint = graph_fingerprint(&data);
+
+	let state_wrapped = Arc::new(RoutingState {
+		data: data,
+		node_index: node_index,
+		reverse_offset: reverse_offset,
+		reverse_edges: reverse_edges,
+		precomputed_cache: Mutex::new(HashMap::new()),
+		graph_fingerprint: graph_fingerprint
+	});
+	let state_wrapped_2 = state_wrapped.clone();
+	let state_wrapped_3 = state_wrapped.clone();
+	let state_wrapped_4 = state_wrapped.clone();
 
 	let mut mount = Mount::new();
 
 	mount.mount("/", Static::new(Path::new("web/")));
-	mount.mount("/api/hello", move |r: &mut Request| get_hello(r, &data_wrapped));
-	mount.mount("/api/graph", move |r: &mut Request| get_graph(r, &data_wrapped_2));
-	mount.mount("/api/route", move |r: &mut Request| get_route(r, &data_wrapped_3));
+	mount.mount("/api/hello", move |r: &mut Request| get_hello(r, &state_wrapped));
+	mount.mount("/api/graph", move |r: &mut Request| get_graph(r, &state_wrapped_2));
+	mount.mount("/api/route", move |r: &mut Request| get_route(r, &state_wrapped_3));
+	mount.mount("/api/route/stream", move |r: &mut Request| get_route_stream(r, &state_wrapped_4));
 
 	println!("server running on http://localhost:8080/");
 
 	Iron::new(mount).http("127.0.0.1:8080").unwrap();
 }
 
-fn get_hello(req: &mut Request, data: &::data::RoutingData) -> IronResult<Response> {
+fn get_hello(req: &mut Request, state: &RoutingState) -> IronResult<Response> {
 	println!("Running get_hello handler, URL path: {:?}", req.url.path);
-	Ok(Response::with((status::Ok, format!("HI! nodes: {}, edges: {}", data.internal_nodes.len(), data.internal_edges.len()))))
+	Ok(Response::with((status::Ok, format!("HI! nodes: {}, edges: {}", state.data.internal_nodes.len(), state.data.internal_edges.len()))))
 }
 
-fn get_graph(req: &mut Request, data: &::data::RoutingData) -> IronResult<Response> {
+fn get_graph(req: &mut Request, state: &RoutingState) -> IronResult<Response> {
 	println!("Running get_graph handler, URL path: {:?}", req.url.path);
-	Ok(Response::with((status::Ok, format!("nodes: {}, edges: {}", data.internal_nodes.len(), data.internal_edges.len()))))
+	Ok(Response::with((status::Ok, format!("nodes: {}, edges: {}", state.data.internal_nodes.len(), state.data.internal_edges.len()))))
 }
 
-fn get_route(req: &mut Request, data: &::data::RoutingData) -> IronResult<Response> {
+fn get_route(req: &mut Request, state: &RoutingState) -> IronResult<Response> {
 	if let Ok(ref query_map) = req.get_ref::<UrlEncodedQuery> () {
-		let source_raw = query_map.get("source").and_then(|list| list.first()).and_then(|string| Some(string.as_str())).unwrap_or("1133751511");
-		let target_raw = query_map.get("target").and_then(|list| list.first()).and_then(|string| Some(string.as_str())).unwrap_or("27281797");
-		let metric_raw = query_map.get("metric").and_then(|list| list.first()).and_then(|string| Some(string.as_str())).unwrap_or("time");
-		let vehicle_raw = query_map.get("vehicle").and_then(|list| list.first()).and_then(|string| Some(string.as_str())).unwrap_or("car");
-
-		let source = itry!(source_raw.parse::<i64>());
-		let target = itry!(target_raw.parse::<i64>());
-
-		let vehice = match vehicle_raw {
-			"car" => ::data::FLAG_CAR,
-			"bike" => ::data::FLAG_BIKE,
-			"walk" => ::data::FLAG_WALK,
-			_ => ::data::FLAG_CAR
+		let data = &state.data;
+
+		let params = parse_common_route_params(query_map)?;
+		let CommonRouteParams { metric_raw, metric, metric_kind, algo_raw, use_astar, bidirectional, .. } = params;
+
+		let source_lat = query_map.get("source_lat").and_then(|list| list.first()).and_then(|string| string.parse::<f64>().ok());
+		let source_lon = query_map.get("source_lon").and_then(|list| list.first()).and_then(|string| string.parse::<f64>().ok());
+		let target_lat = query_map.get("target_lat").and_then(|list| list.first()).and_then(|string| string.parse::<f64>().ok());
+		let target_lon = query_map.get("target_lon").and_then(|list| list.first()).and_then(|string| string.parse::<f64>().ok());
+
+		let snapped_source = match (source_lat, source_lon) {
+			(Some(lat), Some(lon)) => state.node_index.nearest_neighbor(&[lat, lon]).map(|node| data.internal_nodes[node.internal_id]),
+			_ => None
 		};
 
-		let metric = match metric_raw {
-			"time" => edge_cost_time,
-			"distance" => edge_cost_distance,
-			_ => edge_cost_distance
+		let snapped_target = match (target_lat, target_lon) {
+			(Some(lat), Some(lon)) => state.node_index.nearest_neighbor(&[lat, lon]).map(|node| data.internal_nodes[node.internal_id]),
+			_ => None
 		};
 
-		println!("doing routing from {} to {} for vehicle {} with metric {}", source, target, vehice, metric_raw);
+		// Lat/lon snapping (get_route_stream has no equivalent) takes
+		// precedence over the plain OSM id parsed by parse_common_route_params.
+		let source = snapped_source.unwrap_or(params.source);
+		let target = snapped_target.unwrap_or(params.target);
+		let vehice = params.vehice;
+
+		let waypoint_raw: Vec<&str> = query_map.get("waypoints").map(|list| list.iter().map(|s| s.as_str()).collect()).unwrap_or_else(Vec::new);
+
 		let start = PreciseTime::now();
-		let result = run_dijkstra(&data, source, target, vehice, metric);
+
+		let result = if !waypoint_raw.is_empty() {
+			let permute = query_map.get("permute").and_then(|list| list.first()).map(|string| string == "true").unwrap_or(false);
+
+			// Bound the total number of legs before running any full-graph
+			// search: `permute`'s factorial blow-up is already capped below,
+			// but even with `permute` off a single Dijkstra runs per
+			// waypoint, so an unbounded waypoint list is itself a DoS vector.
+			if waypoint_raw.len() > 10 {
+				return Ok(Response::with((status::BadRequest, "too many waypoints (max 10)")));
+			}
+
+			let mut stops = Vec::new();
+
+			for raw in &waypoint_raw {
+				match resolve_waypoint(state, raw) {
+					Some(osm_id) => stops.push(osm_id),
+					None => return Ok(Response::with((status::NotFound)))
+				}
+			}
+
+			run_waypoint_route(&data, &stops, vehice, metric, metric_raw, permute)
+		} else if bidirectional {
+			// Bidirectional search doesn't take a `use_astar` path of its own,
+			// so `algo=astar` has nothing to apply to here either -- log it
+			// the same way the precomputed-table branch below does, instead
+			// of silently dropping it.
+			if use_astar {
+				println!("doing bidirectional routing from {} to {} for vehicle {} with metric {} (ignoring algo {})", source, target, vehice, metric_raw, algo_raw);
+			} else {
+				println!("doing bidirectional routing from {} to {} for vehicle {} with metric {}", source, target, vehice, metric_raw);
+			}
+
+			run_bidirectional_dijkstra(state, source, target, vehice, metric, |_state: &SearchState| {})
+		} else {
+			match load_precomputed_table(state, source, vehice, metric_raw) {
+				Some(table) => {
+					// A precomputed table answers the request directly without
+					// running a search at all, so `algo=astar` has nothing to
+					// apply to here -- it's ignored (and logged) whenever a
+					// table is available. Only `waypoints`/`bidirectional`
+					// outrank a matching table.
+					println!("answering from precomputed table for source {} vehicle {} metric {} (ignoring algo {})", source, vehice, metric_raw, algo_raw);
+
+					let target_internal = data.osm_nodes.get(&target).unwrap().internal_id;
+					let vspeed = vehicle_top_speed(vehice);
+
+					if table.distance[target_internal] >= UNREACHABLE_SENTINEL {
+						None
+					} else {
+						build_route(table.source_internal, target_internal, &table.predecessor, &table.predecessor_edge, &data, &vspeed)
+					}
+				},
+				None => {
+					println!("doing routing from {} to {} for vehicle {} with metric {} (algo {})", source, target, vehice, metric_raw, algo_raw);
+
+					run_dijkstra(&data, source, target, vehice, metric, SearchOptions { metric_kind, use_astar }, |_state: &SearchState| {})
+				}
+			}
+		};
+
 		let end = PreciseTime::now();
 		//println!("route: {:?}", result);
 
@@ -127,14 +335,146 @@ fn get_route(req: &mut Request, data: &::data::RoutingData) -> IronResult<Respon
 	}
 }
 
-fn run_dijkstra<F>(data: &::data::RoutingData, source_osm: i64, target_osm: i64, constraints: u8, cost_func: F) -> Option<Route>
-	where F: Fn(&::data::RoutingEdge, &f64) -> f64 {
-	let vspeed = match constraints {
+// Parsed from the query string by both `get_route` and `get_route_stream` --
+// pulled out so the two handlers can't silently drift apart on how they
+// interpret `vehicle`/`metric`/`algo`/`bidirectional`.
+struct CommonRouteParams<'a> {
+	source: i64,
+	target: i64,
+	vehice: u8,
+	metric_raw: &'a str,
+	metric: fn(&::data::RoutingEdge, &f64) -> f64,
+	metric_kind: Metric,
+	algo_raw: &'a str,
+	use_astar: bool,
+	bidirectional: bool
+}
+
+fn parse_common_route_params<'a>(query_map: &'a HashMap<String, Vec<String>>) -> IronResult<CommonRouteParams<'a>> {
+	let source_raw = query_map.get("source").and_then(|list| list.first()).and_then(|string| Some(string.as_str())).unwrap_or("1133751511");
+	let target_raw = query_map.get("target").and_then(|list| list.first()).and_then(|string| Some(string.as_str())).unwrap_or("27281797");
+	let metric_raw = query_map.get("metric").and_then(|list| list.first()).and_then(|string| Some(string.as_str())).unwrap_or("time");
+	let vehicle_raw = query_map.get("vehicle").and_then(|list| list.first()).and_then(|string| Some(string.as_str())).unwrap_or("car");
+	let algo_raw = query_map.get("algo").and_then(|list| list.first()).and_then(|string| Some(string.as_str())).unwrap_or("dijkstra");
+
+	let source = itry!(source_raw.parse::<i64>());
+	let target = itry!(target_raw.parse::<i64>());
+
+	let vehice = match vehicle_raw {
+		"car" => ::data::FLAG_CAR,
+		"bike" => ::data::FLAG_BIKE,
+		"walk" => ::data::FLAG_WALK,
+		_ => ::data::FLAG_CAR
+	};
+
+	let metric = match metric_raw {
+		"time" => edge_cost_time,
+		"distance" => edge_cost_distance,
+		_ => edge_cost_distance
+	};
+
+	let metric_kind = match metric_raw {
+		"time" => Metric::Time,
+		"distance" => Metric::Distance,
+		_ => Metric::Distance
+	};
+
+	let use_astar = algo_raw == "astar";
+	let bidirectional = query_map.get("bidirectional").and_then(|list| list.first()).map(|string| string == "true").unwrap_or(false);
+
+	Ok(CommonRouteParams { source, target, vehice, metric_raw, metric, metric_kind, algo_raw, use_astar, bidirectional })
+}
+
+// Server-Sent Events body: writes a `SearchState` line roughly every 100ms
+// while the search runs, then a final `event: done` with the route (or null,
+// if none was found). Runs the exact same `run_dijkstra`/
+// `run_bidirectional_dijkstra` search loops `get_route` uses, just with a
+// progress closure attached, so streaming can't fall behind their
+// astar/bidirectional support.
+struct RouteStreamBody {
+	state: Arc<RoutingState>,
+	source: i64,
+	target: i64,
+	constraints: u8,
+	cost_func: fn(&::data::RoutingEdge, &f64) -> f64,
+	metric_kind: Metric,
+	use_astar: bool,
+	bidirectional: bool
+}
+
+impl WriteBody for RouteStreamBody {
+	fn write_body(&mut self, res: &mut Write) -> io::Result<()> {
+		let mut last_emit = PreciseTime::now();
+
+		let route = {
+			let mut progress = |state: &SearchState| {
+				if last_emit.to(PreciseTime::now()).num_milliseconds() >= 100 {
+					let _ = write!(res, "data: {}\n\n", json::encode(state).unwrap());
+					let _ = res.flush();
+
+					last_emit = PreciseTime::now();
+				}
+			};
+
+			if self.bidirectional {
+				run_bidirectional_dijkstra(&self.state, self.source, self.target, self.constraints, self.cost_func, &mut progress)
+			} else {
+				run_dijkstra(&self.state.data, self.source, self.target, self.constraints, self.cost_func, SearchOptions { metric_kind: self.metric_kind, use_astar: self.use_astar }, &mut progress)
+			}
+		};
+
+		write!(res, "event: done\ndata: {}\n\n", json::encode(&route).unwrap())?;
+		res.flush()?;
+
+		Ok(())
+	}
+}
+
+fn get_route_stream(req: &mut Request, state: &Arc<RoutingState>) -> IronResult<Response> {
+	if let Ok(ref query_map) = req.get_ref::<UrlEncodedQuery> () {
+		let params = parse_common_route_params(query_map)?;
+
+		println!("streaming route from {} to {} for vehicle {} with metric {} (algo {}, bidirectional {})", params.source, params.target, params.vehice, params.metric_raw, params.algo_raw, params.bidirectional);
+
+		let body = RouteStreamBody {
+			state: state.clone(),
+			source: params.source,
+			target: params.target,
+			constraints: params.vehice,
+			cost_func: params.metric,
+			metric_kind: params.metric_kind,
+			use_astar: params.use_astar,
+			bidirectional: params.bidirectional
+		};
+
+		let mut response = Response::with((status::Ok, Box::new(body) as Box<WriteBody + Send>));
+		response.headers.set(ContentType(Mime(TopLevel::Text, SubLevel::Ext("event-stream".to_owned()), vec![])));
+
+		Ok(response)
+	} else {
+		Ok(Response::with((status::InternalServerError)))
+	}
+}
+
+fn vehicle_top_speed(constraints: u8) -> f64 {
+	match constraints {
 		::data::FLAG_CAR => 130.0 / 3.6,
 		::data::FLAG_BIKE => 15.0 / 3.6,
 		::data::FLAG_WALK => 5.0 / 3.6,
 		_ => 130.0 / 3.6
-	};
+	}
+}
+
+// Single-source-single-target Dijkstra, optionally guided by an A* heuristic.
+// `progress` is called roughly every 100ms with a snapshot of the search so
+// far; pass a no-op closure (`|_| {}`) when nobody is watching, as `get_route`
+// does, or a closure that writes an SSE event, as `get_route_stream` does --
+// both endpoints share this loop for single-direction search (and
+// `run_bidirectional_dijkstra` for bidirectional search), so streaming can't
+// drift out of sync with the plain route endpoint's A* support.
+fn run_dijkstra<F, P>(data: &::data::RoutingData, source_osm: i64, target_osm: i64, constraints: u8, cost_func: F, options: SearchOptions, mut progress: P) -> Option<Route>
+	where F: Fn(&::data::RoutingEdge, &f64) -> f64, P: FnMut(&SearchState) {
+	let vspeed = vehicle_top_speed(constraints);
 
 	let mut distance = vec![f64::INFINITY; data.internal_nodes.len()];
 	let mut predecessor = vec![0; data.internal_nodes.len()];
@@ -142,20 +482,115 @@ fn run_dijkstra<F>(data: &::data::RoutingData, source_osm: i64, target_osm: i64,
 
 	let source = data.osm_nodes.get(&source_osm).unwrap().internal_id;
 	let target = data.osm_nodes.get(&target_osm).unwrap().internal_id;
+	let target_pos = data.osm_nodes.get(&target_osm).unwrap().position;
+
+	// Admissible lower bound on the remaining cost to the target; zero disables it,
+	// which degrades the search back to plain Dijkstra.
+	let heuristic = |node: usize| -> f64 {
+		if !options.use_astar {
+			return 0.0;
+		}
+
+		let osm_id = data.internal_nodes[node];
+		let pos = data.osm_nodes.get(&osm_id).unwrap().position;
+		let straight_line = haversine_distance(pos.lat, pos.lon, target_pos.lat, target_pos.lon);
+
+		match options.metric_kind {
+			Metric::Distance => straight_line,
+			Metric::Time => straight_line / vspeed
+		}
+	};
 
 	let mut heap = BinaryHeap::new();
 
 	distance[source] = 0.0;
-	heap.push(HeapEntry { node: source, cost: 0.0 });
+	heap.push(HeapEntry { node: source, cost: 0.0, priority: heuristic(source) });
 
 	println!("begin dijkstra");
 
-	while let Some(HeapEntry { node, cost }) = heap.pop() {
+	let search_start = PreciseTime::now();
+	let mut last_emit = search_start;
+	let mut settled_count = 0usize;
+	let status_interval_ms = 100;
+
+	while let Some(HeapEntry { node, cost, priority: _ }) = heap.pop() {
+		if cost > distance[node] { continue; }
+
+		settled_count += 1;
+
 		if node == target {
 			println!("found route");
 			return build_route(source, target, &predecessor, &predecessor_edge, &data, &vspeed);
 		}
 
+		let (start, end) = offset_lookup(&node, &data);
+		let edges = &data.internal_edges[start..end];
+
+		for (i, edge) in edges.iter().enumerate() {
+			if constraints & edge.constraints == 0 {
+				continue;
+			}
+			let neighbor_cost = cost + cost_func(&edge, &vspeed);
+
+			if neighbor_cost < distance[edge.target] {
+				distance[edge.target] = neighbor_cost;
+				predecessor[edge.target] = node;
+				predecessor_edge[edge.target] = i + start;
+				heap.push(HeapEntry { node: edge.target, cost: neighbor_cost, priority: neighbor_cost + heuristic(edge.target) });
+			}
+		}
+
+		if last_emit.to(PreciseTime::now()).num_milliseconds() >= status_interval_ms {
+			progress(&SearchState {
+				settled: settled_count,
+				frontier: heap.len(),
+				best_known: distance[target],
+				elapsed_ms: search_start.to(PreciseTime::now()).num_milliseconds()
+			});
+
+			last_emit = PreciseTime::now();
+		}
+	}
+
+	return None;
+}
+
+// Resolves a single `waypoints` entry, either a "lat,lon" pair snapped via the
+// R-tree or a bare OSM node id.
+fn resolve_waypoint(state: &RoutingState, raw: &str) -> Option<i64> {
+	if raw.contains(',') {
+		let mut parts = raw.splitn(2, ',');
+		let lat = parts.next().and_then(|s| s.trim().parse::<f64>().ok());
+		let lon = parts.next().and_then(|s| s.trim().parse::<f64>().ok());
+
+		match (lat, lon) {
+			(Some(lat), Some(lon)) => state.node_index.nearest_neighbor(&[lat, lon]).map(|node| state.data.internal_nodes[node.internal_id]),
+			_ => None
+		}
+	} else {
+		raw.trim().parse::<i64>().ok()
+	}
+}
+
+// Runs Dijkstra to completion from a single source instead of stopping at the
+// first target, so the resulting distance/predecessor arrays can answer a
+// route to any of several waypoints without re-searching.
+fn run_dijkstra_full<F>(data: &::data::RoutingData, source_osm: i64, constraints: u8, cost_func: F) -> (usize, Vec<f64>, Vec<usize>, Vec<usize>)
+	where F: Fn(&::data::RoutingEdge, &f64) -> f64 {
+	let vspeed = vehicle_top_speed(constraints);
+
+	let mut distance = vec![f64::INFINITY; data.internal_nodes.len()];
+	let mut predecessor = vec![0; data.internal_nodes.len()];
+	let mut predecessor_edge = vec![0; data.internal_nodes.len()];
+
+	let source = data.osm_nodes.get(&source_osm).unwrap().internal_id;
+
+	let mut heap = BinaryHeap::new();
+
+	distance[source] = 0.0;
+	heap.push(HeapEntry { node: source, cost: 0.0, priority: 0.0 });
+
+	while let Some(HeapEntry { node, cost, priority: _ }) = heap.pop() {
 		if cost > distance[node] { continue; }
 
 		let (start, end) = offset_lookup(&node, &data);
@@ -165,18 +600,238 @@ fn run_dijkstra<F>(data: &::data::RoutingData, source_osm: i64, target_osm: i64,
 			if constraints & edge.constraints == 0 {
 				continue;
 			}
-			let neighbor = HeapEntry { node: edge.target, cost: cost + cost_func(&edge, &vspeed) };
+			let neighbor_cost = cost + cost_func(&edge, &vspeed);
 
-			if neighbor.cost < distance[neighbor.node] {
-				distance[edge.target] = neighbor.cost;
+			if neighbor_cost < distance[edge.target] {
+				distance[edge.target] = neighbor_cost;
 				predecessor[edge.target] = node;
 				predecessor_edge[edge.target] = i + start;
-				heap.push(neighbor);
+				heap.push(HeapEntry { node: edge.target, cost: neighbor_cost, priority: neighbor_cost });
 			}
 		}
 	}
 
-	return None;
+	return (source, distance, predecessor, predecessor_edge);
+}
+
+// Runs a full single-source Dijkstra for (source, vehicle, metric) and writes
+// the resulting distance/predecessor arrays to disk so `get_route` can answer
+// any target for that source without searching again.
+pub fn precompute(data: &::data::RoutingData, source_osm: i64, vehicle: u8, metric_raw: &str) -> io::Result<()> {
+	let metric = normalize_metric(metric_raw);
+
+	let cost_func: fn(&::data::RoutingEdge, &f64) -> f64 = match metric {
+		"time" => edge_cost_time,
+		_ => edge_cost_distance
+	};
+
+	let (source_internal, distance, predecessor, predecessor_edge) = run_dijkstra_full(&data, source_osm, vehicle, cost_func);
+
+	// f64::INFINITY round-trips through rustc_serialize's JSON encoding as
+	// `null`, which then fails to decode, so unreachable nodes are flagged
+	// with a large finite sentinel instead.
+	let distance: Vec<f64> = distance.into_iter().map(|d| if d.is_infinite() { UNREACHABLE_SENTINEL } else { d }).collect();
+
+	let table = PrecomputedTable {
+		source_internal: source_internal,
+		vehicle: vehicle,
+		metric: metric.to_owned(),
+		graph_fingerprint: graph_fingerprint(&data),
+		distance: distance,
+		predecessor: predecessor,
+		predecessor_edge: predecessor_edge
+	};
+
+	fs::create_dir_all("precompute")?;
+	fs::write(precompute_cache_path(source_internal, vehicle, metric), json::encode(&table).unwrap())?;
+
+	println!("precomputed distance table for source {} vehicle {} metric {}", source_osm, vehicle, metric);
+
+	return Ok(());
+}
+
+// Loads a precomputed table for the given source/vehicle/metric, rejecting it
+// if it doesn't exist, doesn't match the request, or was computed against a
+// different graph. Tables are kept in memory after their first load so a
+// popular source doesn't re-read and re-parse its table off disk on every
+// request.
+fn load_precomputed_table(state: &RoutingState, source_osm: i64, vehicle: u8, metric_raw: &str) -> Option<Arc<PrecomputedTable>> {
+	// `metric_raw` comes straight from the query string; normalize it to the
+	// fixed set of known metrics before it can reach a filesystem path.
+	let metric = normalize_metric(metric_raw);
+
+	let data = &state.data;
+	let source_internal = data.osm_nodes.get(&source_osm)?.internal_id;
+	let key = (source_internal, vehicle, metric.to_owned());
+
+	if let Some(table) = state.precomputed_cache.lock().unwrap().get(&key) {
+		return Some(table.clone());
+	}
+
+	let path = precompute_cache_path(source_internal, vehicle, metric);
+
+	let contents = fs::read_to_string(path).ok()?;
+	let table: PrecomputedTable = json::decode(&contents).ok()?;
+
+	if table.source_internal != source_internal || table.vehicle != vehicle || table.metric != metric {
+		return None;
+	}
+
+	if table.graph_fingerprint != state.graph_fingerprint {
+		println!("precomputed table for source {} is stale, ignoring", source_osm);
+		return None;
+	}
+
+	let table = Arc::new(table);
+	state.precomputed_cache.lock().unwrap().insert(key, table.clone());
+
+	return Some(table);
+}
+
+// Restricts an arbitrary query-supplied metric to the fixed set this server
+// understands. Every other use of a `metric` query parameter already funnels
+// through a match with the same default, so this just makes that the only
+// path a metric can take before it is used to build a filesystem path.
+fn normalize_metric(metric_raw: &str) -> &'static str {
+	match metric_raw {
+		"time" => "time",
+		_ => "distance"
+	}
+}
+
+fn precompute_cache_path(source_internal: usize, vehicle: u8, metric: &str) -> String {
+	return format!("precompute/{}_{}_{}.bin", source_internal, vehicle, metric);
+}
+
+// Fingerprint of the graph's full shape (not just its size), used to reject a
+// precomputed table that no longer matches the loaded data instead of
+// silently misrouting. Edges are folded into the hasher in iteration order
+// (rather than summed commutatively) so that swapping two edges, or moving an
+// edge to a different source node, changes the fingerprint even though such a
+// swap wouldn't change a simple sum of edge fields.
+fn graph_fingerprint(data: &::data::RoutingData) -> u64 {
+	let mut hasher = DefaultHasher::new();
+
+	data.internal_nodes.len().hash(&mut hasher);
+	data.internal_offset.hash(&mut hasher);
+
+	for edge in data.internal_edges.iter() {
+		edge.target.hash(&mut hasher);
+		edge.length.to_bits().hash(&mut hasher);
+		edge.speed.to_bits().hash(&mut hasher);
+		edge.constraints.hash(&mut hasher);
+	}
+
+	return hasher.finish();
+}
+
+// Stitches a route through an ordered list of waypoint OSM ids. Precomputes
+// the full leg matrix with one Dijkstra run per waypoint, then optionally
+// tries every ordering of the interior stops to minimize total cost.
+fn run_waypoint_route<F>(data: &::data::RoutingData, stops: &Vec<i64>, constraints: u8, cost_func: F, metric_raw: &str, permute: bool) -> Option<Route>
+	where F: Fn(&::data::RoutingEdge, &f64) -> f64 {
+	if stops.len() < 2 || stops.len() > 10 {
+		return None;
+	}
+
+	let vspeed = vehicle_top_speed(constraints);
+	let n = stops.len();
+
+	let mut legs: Vec<Vec<Option<Route>>> = Vec::with_capacity(n);
+
+	for i in 0..n {
+		let (source, distance, predecessor, predecessor_edge) = run_dijkstra_full(&data, stops[i], constraints, &cost_func);
+
+		let mut row = Vec::with_capacity(n);
+
+		for j in 0..n {
+			let target = data.osm_nodes.get(&stops[j]).unwrap().internal_id;
+
+			if i == j || distance[target] == f64::INFINITY {
+				row.push(None);
+			} else {
+				row.push(build_route(source, target, &predecessor, &predecessor_edge, &data, &vspeed));
+			}
+		}
+
+		legs.push(row);
+	}
+
+	let leg_cost = |route: &Route| -> f64 {
+		if metric_raw == "time" { route.time } else { route.distance }
+	};
+
+	let order = if permute && n > 2 && n <= 10 {
+		let interior: Vec<usize> = (1..n - 1).collect();
+		let mut best_order: Option<Vec<usize>> = None;
+		let mut best_cost = f64::INFINITY;
+
+		for permutation in permutations_of(&interior) {
+			let mut full_order = Vec::with_capacity(n);
+			full_order.push(0);
+			full_order.extend(permutation);
+			full_order.push(n - 1);
+
+			let mut total = 0.0;
+			let mut reachable = true;
+
+			for window in full_order.windows(2) {
+				match &legs[window[0]][window[1]] {
+					Some(route) => total += leg_cost(route),
+					None => { reachable = false; break; }
+				}
+			}
+
+			if reachable && total < best_cost {
+				best_cost = total;
+				best_order = Some(full_order);
+			}
+		}
+
+		match best_order {
+			Some(order) => order,
+			None => (0..n).collect()
+		}
+	} else {
+		(0..n).collect()
+	};
+
+	let mut result = Route { distance: 0.0, time: 0.0, path: Vec::new() };
+
+	for window in order.windows(2) {
+		let leg = match &legs[window[0]][window[1]] {
+			Some(leg) => leg,
+			None => return None
+		};
+
+		result.distance += leg.distance;
+		result.time += leg.time;
+		result.path.extend(leg.path.iter().cloned());
+	}
+
+	return Some(result);
+}
+
+// All permutations of a small index set, used to try waypoint orderings.
+fn permutations_of(indices: &Vec<usize>) -> Vec<Vec<usize>> {
+	if indices.len() <= 1 {
+		return vec![indices.clone()];
+	}
+
+	let mut result = Vec::new();
+
+	for i in 0..indices.len() {
+		let mut rest = indices.clone();
+		let chosen = rest.remove(i);
+
+		for mut tail in permutations_of(&rest) {
+			let mut permutation = vec![chosen];
+			permutation.append(&mut tail);
+			result.push(permutation);
+		}
+	}
+
+	return result;
 }
 
 fn offset_lookup(node: &usize, data: &::data::RoutingData) -> (usize, usize) {
@@ -197,6 +852,241 @@ fn offset_lookup(node: &usize, data: &::data::RoutingData) -> (usize, usize) {
 	return (start, end);
 }
 
+// Transposes `internal_edges`/`internal_offset` once at load time so a
+// backward search can walk incoming edges exactly like `offset_lookup` walks
+// outgoing ones.
+fn build_reverse_graph(data: &::data::RoutingData) -> (Vec<usize>, Vec<ReverseEdge>) {
+	let node_count = data.internal_nodes.len();
+	let mut incoming_count = vec![0usize; node_count];
+
+	for node in 0..node_count {
+		let (start, end) = offset_lookup(&node, &data);
+
+		for edge in &data.internal_edges[start..end] {
+			incoming_count[edge.target] += 1;
+		}
+	}
+
+	let mut reverse_offset = vec![0usize; node_count + 1];
+
+	for node in 0..node_count {
+		reverse_offset[node + 1] = reverse_offset[node] + incoming_count[node];
+	}
+
+	let mut cursor = reverse_offset.clone();
+	let mut reverse_edges = vec![ReverseEdge { node: 0, edge: 0 }; reverse_offset[node_count]];
+
+	for node in 0..node_count {
+		let (start, end) = offset_lookup(&node, &data);
+
+		for (i, edge) in data.internal_edges[start..end].iter().enumerate() {
+			let pos = cursor[edge.target];
+			reverse_edges[pos] = ReverseEdge { node: node, edge: start + i };
+			cursor[edge.target] += 1;
+		}
+	}
+
+	return (reverse_offset, reverse_edges);
+}
+
+fn reverse_offset_lookup(node: &usize, reverse_offset: &Vec<usize>) -> (usize, usize) {
+	return (reverse_offset[*node], reverse_offset[*node + 1]);
+}
+
+// Runs forward and backward Dijkstra frontiers simultaneously, alternating
+// on whichever side has the cheaper top-of-heap cost, and stops once the best
+// meeting-node sum `mu` can no longer be beaten by either frontier.
+fn run_bidirectional_dijkstra<F, P>(state: &RoutingState, source_osm: i64, target_osm: i64, constraints: u8, cost_func: F, mut progress: P) -> Option<Route>
+	where F: Fn(&::data::RoutingEdge, &f64) -> f64, P: FnMut(&SearchState) {
+	let data = &state.data;
+	let vspeed = vehicle_top_speed(constraints);
+	let node_count = data.internal_nodes.len();
+
+	let mut distance_fwd = vec![f64::INFINITY; node_count];
+	let mut distance_bwd = vec![f64::INFINITY; node_count];
+	let mut predecessor_fwd = vec![0; node_count];
+	let mut predecessor_edge_fwd = vec![0; node_count];
+	let mut predecessor_bwd = vec![0; node_count];
+	let mut predecessor_edge_bwd = vec![0; node_count];
+	let mut settled_fwd = vec![false; node_count];
+	let mut settled_bwd = vec![false; node_count];
+
+	let source = data.osm_nodes.get(&source_osm).unwrap().internal_id;
+	let target = data.osm_nodes.get(&target_osm).unwrap().internal_id;
+
+	let mut heap_fwd = BinaryHeap::new();
+	let mut heap_bwd = BinaryHeap::new();
+
+	distance_fwd[source] = 0.0;
+	distance_bwd[target] = 0.0;
+	heap_fwd.push(HeapEntry { node: source, cost: 0.0, priority: 0.0 });
+	heap_bwd.push(HeapEntry { node: target, cost: 0.0, priority: 0.0 });
+
+	let mut mu = f64::INFINITY;
+	let mut meeting_node: Option<usize> = None;
+
+	println!("begin bidirectional dijkstra");
+
+	let search_start = PreciseTime::now();
+	let mut last_emit = search_start;
+	let mut settled_count = 0usize;
+	let status_interval_ms = 100;
+
+	loop {
+		while let Some(top) = heap_fwd.peek() {
+			if top.cost > distance_fwd[top.node] { heap_fwd.pop(); } else { break; }
+		}
+
+		while let Some(top) = heap_bwd.peek() {
+			if top.cost > distance_bwd[top.node] { heap_bwd.pop(); } else { break; }
+		}
+
+		let top_fwd = heap_fwd.peek().map(|entry| entry.cost);
+		let top_bwd = heap_bwd.peek().map(|entry| entry.cost);
+
+		let (cost_fwd, cost_bwd) = match (top_fwd, top_bwd) {
+			(Some(cf), Some(cb)) => (cf, cb),
+			_ => break
+		};
+
+		if cost_fwd + cost_bwd >= mu {
+			break;
+		}
+
+		if cost_fwd <= cost_bwd {
+			let HeapEntry { node, cost, priority: _ } = heap_fwd.pop().unwrap();
+			settled_fwd[node] = true;
+
+			if settled_bwd[node] && distance_fwd[node] + distance_bwd[node] < mu {
+				mu = distance_fwd[node] + distance_bwd[node];
+				meeting_node = Some(node);
+			}
+
+			let (start, end) = offset_lookup(&node, &data);
+
+			for (i, edge) in data.internal_edges[start..end].iter().enumerate() {
+				if constraints & edge.constraints == 0 {
+					continue;
+				}
+
+				let neighbor_cost = cost + cost_func(&edge, &vspeed);
+
+				if neighbor_cost < distance_fwd[edge.target] {
+					distance_fwd[edge.target] = neighbor_cost;
+					predecessor_fwd[edge.target] = node;
+					predecessor_edge_fwd[edge.target] = i + start;
+					heap_fwd.push(HeapEntry { node: edge.target, cost: neighbor_cost, priority: neighbor_cost });
+
+					if settled_bwd[edge.target] && neighbor_cost + distance_bwd[edge.target] < mu {
+						mu = neighbor_cost + distance_bwd[edge.target];
+						meeting_node = Some(edge.target);
+					}
+				}
+			}
+		} else {
+			let HeapEntry { node, cost, priority: _ } = heap_bwd.pop().unwrap();
+			settled_bwd[node] = true;
+
+			if settled_fwd[node] && distance_fwd[node] + distance_bwd[node] < mu {
+				mu = distance_fwd[node] + distance_bwd[node];
+				meeting_node = Some(node);
+			}
+
+			let (start, end) = reverse_offset_lookup(&node, &state.reverse_offset);
+
+			for reverse_edge in &state.reverse_edges[start..end] {
+				let edge = &data.internal_edges[reverse_edge.edge];
+
+				if constraints & edge.constraints == 0 {
+					continue;
+				}
+
+				let predecessor = reverse_edge.node;
+				let neighbor_cost = cost + cost_func(&edge, &vspeed);
+
+				if neighbor_cost < distance_bwd[predecessor] {
+					distance_bwd[predecessor] = neighbor_cost;
+					predecessor_bwd[predecessor] = node;
+					predecessor_edge_bwd[predecessor] = reverse_edge.edge;
+					heap_bwd.push(HeapEntry { node: predecessor, cost: neighbor_cost, priority: neighbor_cost });
+
+					if settled_fwd[predecessor] && distance_fwd[predecessor] + neighbor_cost < mu {
+						mu = distance_fwd[predecessor] + neighbor_cost;
+						meeting_node = Some(predecessor);
+					}
+				}
+			}
+		}
+
+		settled_count += 1;
+
+		if last_emit.to(PreciseTime::now()).num_milliseconds() >= status_interval_ms {
+			progress(&SearchState {
+				settled: settled_count,
+				frontier: heap_fwd.len() + heap_bwd.len(),
+				best_known: mu,
+				elapsed_ms: search_start.to(PreciseTime::now()).num_milliseconds()
+			});
+
+			last_emit = PreciseTime::now();
+		}
+	}
+
+	let meeting = match meeting_node {
+		Some(node) => node,
+		None => return None
+	};
+
+	let forward_leg = match build_route(source, meeting, &predecessor_fwd, &predecessor_edge_fwd, &data, &vspeed) {
+		Some(route) => route,
+		None => return None
+	};
+
+	let backward_leg = build_backward_leg(meeting, target, &predecessor_bwd, &predecessor_edge_bwd, &data, &vspeed);
+
+	let mut result = Route { distance: 0.0, time: 0.0, path: Vec::new() };
+
+	result.distance = forward_leg.distance + backward_leg.distance;
+	result.time = forward_leg.time + backward_leg.time;
+	result.path.extend(forward_leg.path);
+	result.path.extend(backward_leg.path);
+
+	println!("found route (bidirectional), meeting node {}", meeting);
+
+	return Some(result);
+}
+
+// Walks the backward search tree from the meeting node to the target in
+// forward temporal order, mirroring `build_route`'s conventions (start node
+// excluded, end node included).
+fn build_backward_leg(meeting: usize, target: usize, predecessor_bwd: &Vec<usize>, predecessor_edge_bwd: &Vec<usize>, data: &::data::RoutingData, vspeed: &f64) -> Route {
+	let mut result = Route { distance: 0.0, time: 0.0, path: Vec::new() };
+
+	let mut node = meeting;
+
+	while node != target {
+		let next = predecessor_bwd[node];
+		let edge = predecessor_edge_bwd[node];
+
+		let mut speed = data.internal_edges[edge].speed;
+
+		if *vspeed < speed {
+			speed = *vspeed;
+		}
+
+		let osm_id = data.internal_nodes[next];
+		let pos = data.osm_nodes.get(&osm_id).unwrap().position;
+
+		result.path.push([pos.lat, pos.lon]);
+		result.distance += data.internal_edges[edge].length;
+		result.time += data.internal_edges[edge].length / speed;
+
+		node = next;
+	}
+
+	return result;
+}
+
 
 fn build_route(source: usize, target: usize, predecessor: &Vec<usize>, predecessor_edge: &Vec<usize>, data: &::data::RoutingData, vspeed: &f64) -> Option<Route> {
 	let mut result = Route { distance: 0.0, time: 0.0, path: Vec::new() };
@@ -251,7 +1141,144 @@ fn edge_cost_time(edge: &::data::RoutingEdge, vspeed: &f64) -> f64 {
 fn test_dijkstra() {
 	let data = ::parser::build_dummy_data();
 
-	let path = run_dijkstra(&data, 5000, 5003, ::data::FLAG_CAR, edge_cost_time);
+	let path = run_dijkstra(&data, 5000, 5003, ::data::FLAG_CAR, edge_cost_time, SearchOptions { metric_kind: Metric::Time, use_astar: false }, |_state: &SearchState| {});
 
 	println!("path: {:?}", path);
 }
+
+// The A* heuristic is an admissible lower bound, so it should never change
+// the optimal cost, only how quickly the search finds it.
+#[test]
+fn test_astar_matches_plain_dijkstra() {
+	let data = ::parser::build_dummy_data();
+
+	let plain = run_dijkstra(&data, 5000, 5003, ::data::FLAG_CAR, edge_cost_distance, SearchOptions { metric_kind: Metric::Distance, use_astar: false }, |_state: &SearchState| {})
+		.expect("plain dijkstra should find a route on the dummy graph");
+	let astar = run_dijkstra(&data, 5000, 5003, ::data::FLAG_CAR, edge_cost_distance, SearchOptions { metric_kind: Metric::Distance, use_astar: true }, |_state: &SearchState| {})
+		.expect("astar should find a route on the dummy graph");
+
+	assert!((plain.distance - astar.distance).abs() < 1e-6);
+	assert!((plain.time - astar.time).abs() < 1e-6);
+}
+
+// SearchState is what /api/route/stream emits as an SSE progress event, so
+// it has to survive a JSON round trip unchanged. (The 100ms emit gate in
+// run_dijkstra/run_bidirectional_dijkstra is wall-clock driven and the dummy
+// graph searches in well under that, so asserting the callback fires
+// mid-search would make this test flaky rather than meaningful; this instead
+// covers the shape of what gets sent, and the next test covers that a real
+// (non-no-op) progress closure runs through the search without issue.)
+#[test]
+fn test_search_state_json_round_trip() {
+	let state = SearchState { settled: 12, frontier: 3, best_known: 42.5, elapsed_ms: 7 };
+
+	let encoded = json::encode(&state).unwrap();
+	let decoded: SearchState = json::decode(&encoded).unwrap();
+
+	assert_eq!(decoded.settled, state.settled);
+	assert_eq!(decoded.frontier, state.frontier);
+	assert_eq!(decoded.best_known, state.best_known);
+	assert_eq!(decoded.elapsed_ms, state.elapsed_ms);
+}
+
+#[test]
+fn test_run_dijkstra_runs_with_a_recording_progress_closure() {
+	let data = ::parser::build_dummy_data();
+	let mut calls: Vec<SearchState> = Vec::new();
+
+	let route = run_dijkstra(&data, 5000, 5003, ::data::FLAG_CAR, edge_cost_distance, SearchOptions { metric_kind: Metric::Distance, use_astar: false }, |state: &SearchState| {
+		calls.push(state.clone());
+	});
+
+	assert!(route.is_some());
+}
+
+// At 50N, a node offset by 0.01 degrees of longitude (~0.72km) is closer than
+// one offset by 0.007 degrees of latitude (~0.78km), even though the latter
+// has the smaller squared-degree delta -- regression test for the ranking
+// bug in NodeLocation::distance_2.
+#[test]
+fn test_nearest_neighbor_ranks_by_real_world_distance() {
+	let index = RTree::bulk_load(vec![
+		NodeLocation { lat: 50.0, lon: 0.01, internal_id: 1 },
+		NodeLocation { lat: 50.007, lon: 0.0, internal_id: 2 }
+	]);
+
+	let nearest = index.nearest_neighbor(&[50.0, 0.0]).expect("should find a nearest node");
+
+	assert_eq!(nearest.internal_id, 1);
+}
+
+// With `permute` on, the waypoint search tries every ordering of the
+// interior stops, so it should never be more expensive than the identity
+// order it falls back to without `permute`.
+#[test]
+fn test_waypoint_permutation_is_no_worse_than_identity_order() {
+	let data = ::parser::build_dummy_data();
+	let stops = vec![5000, 5003, 5001];
+
+	let identity = run_waypoint_route(&data, &stops, ::data::FLAG_CAR, edge_cost_distance, "distance", false)
+		.expect("identity order should find a route on the dummy graph");
+	let permuted = run_waypoint_route(&data, &stops, ::data::FLAG_CAR, edge_cost_distance, "distance", true)
+		.expect("permutation search should find a route on the dummy graph");
+
+	assert!(permuted.distance <= identity.distance + 1e-6);
+}
+
+// The meeting node found by the bidirectional search should yield the same
+// optimal cost as a plain single-direction Dijkstra run.
+#[test]
+fn test_bidirectional_matches_plain_dijkstra() {
+	let data = ::parser::build_dummy_data();
+
+	let node_index = RTree::bulk_load(data.osm_nodes.values().map(|node| NodeLocation {
+		lat: node.position.lat,
+		lon: node.position.lon,
+		internal_id: node.internal_id
+	}).collect());
+	let (reverse_offset, reverse_edges) = build_reverse_graph(&data);
+	let fingerprint = graph_fingerprint(&data);
+
+	let state = RoutingState {
+		data: data,
+		node_index: node_index,
+		reverse_offset: reverse_offset,
+		reverse_edges: reverse_edges,
+		precomputed_cache: Mutex::new(HashMap::new()),
+		graph_fingerprint: fingerprint
+	};
+
+	let plain = run_dijkstra(&state.data, 5000, 5003, ::data::FLAG_CAR, edge_cost_distance, SearchOptions { metric_kind: Metric::Distance, use_astar: false }, |_state: &SearchState| {})
+		.expect("plain dijkstra should find a route on the dummy graph");
+	let bidirectional = run_bidirectional_dijkstra(&state, 5000, 5003, ::data::FLAG_CAR, edge_cost_distance, |_state: &SearchState| {})
+		.expect("bidirectional search should find a route on the dummy graph");
+
+	assert!((plain.distance - bidirectional.distance).abs() < 1e-6);
+}
+
+// A PrecomputedTable must survive a JSON encode/decode round trip unchanged,
+// including the UNREACHABLE_SENTINEL substitution for unreachable nodes
+// (rustc_serialize turns f64::INFINITY into `null`, which fails to decode).
+#[test]
+fn test_precomputed_table_json_round_trip() {
+	let table = PrecomputedTable {
+		source_internal: 3,
+		vehicle: ::data::FLAG_CAR,
+		metric: "distance".to_owned(),
+		graph_fingerprint: 0x1234_5678_9abc_def0,
+		distance: vec![0.0, 42.5, UNREACHABLE_SENTINEL],
+		predecessor: vec![0, 0, 1],
+		predecessor_edge: vec![0, 2, 5]
+	};
+
+	let encoded = json::encode(&table).unwrap();
+	let decoded: PrecomputedTable = json::decode(&encoded).unwrap();
+
+	assert_eq!(decoded.source_internal, table.source_internal);
+	assert_eq!(decoded.vehicle, table.vehicle);
+	assert_eq!(decoded.metric, table.metric);
+	assert_eq!(decoded.graph_fingerprint, table.graph_fingerprint);
+	assert_eq!(decoded.distance, table.distance);
+	assert_eq!(decoded.predecessor, table.predecessor);
+	assert_eq!(decoded.predecessor_edge, table.predecessor_edge);
+}